@@ -1,42 +1,28 @@
+use std::collections::HashSet;
+
 use bevy::{prelude::{
-    default, Assets, Camera3dBundle, Commands, Mesh, PointLight,
-    PointLightBundle, ResMut, Transform, Vec3, MaterialMeshBundle, Material,
-}, render::{mesh::{VertexAttributeValues, Indices}, render_resource::{AsBindGroup, ShaderRef, PrimitiveTopology}}, reflect::TypeUuid};
+    default, Assets, Camera3dBundle, Color, Commands, Component, Entity, IVec2, Mesh, PbrBundle,
+    PointLight, PointLightBundle, Query, Res, ResMut, StandardMaterial, Time, Transform, Vec2,
+    Vec3, MaterialMeshBundle, Material,
+}, render::{mesh::{SphereKind, SphereMeshBuilder, VertexAttributeValues, Indices}, render_resource::{AsBindGroup, ShaderRef, ShaderType, PrimitiveTopology}}, reflect::TypeUuid};
 use bevy_atmosphere::prelude::AtmosphereCamera;
-use smooth_bevy_cameras::controllers::orbit::{OrbitCameraBundle, OrbitCameraController};
+use noise::{NoiseFn, Simplex};
+use smooth_bevy_cameras::{
+    controllers::orbit::{OrbitCameraBundle, OrbitCameraController},
+    LookTransform,
+};
 use itertools::Itertools;
 
+// spawn a mountainous Planet instead of the flat land
+const SPAWN_PLANET: bool = false;
+
 // setup for 3D scene
 pub fn setup_world(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<LandMaterial>>,
+    mut standard_materials: ResMut<Assets<StandardMaterial>>,
 ) {
-    // land
-    let mut land = Mesh::from(Land {
-        size: 100.0,
-        num_vertices: 100,
-    });
-    if let Some(VertexAttributeValues::Float32x3(
-        positions,
-    )) = land.attribute(Mesh::ATTRIBUTE_POSITION)
-    {
-        let colors: Vec<[f32; 4]> = positions
-            .iter()
-            .map(|[r, g, b]| {
-                [
-                    (1. - *r) / 2.,
-                    (1. - *g) / 2.,
-                    (1. - *b) / 2.,
-                    1.,
-                ]
-            })
-            .collect();
-        land.insert_attribute(
-            Mesh::ATTRIBUTE_COLOR,
-            colors,
-        );
-    }
     // Light
     commands.spawn(PointLightBundle {
         point_light: PointLight {
@@ -56,12 +42,162 @@ pub fn setup_world(
             Vec3::new(0., 0., 0.),
             Vec3::Y,
         ));
-    
-    commands.spawn(MaterialMeshBundle {
-        mesh: meshes.add(land),
-        transform: Transform::from_xyz(0.0, 0.5, 0.0),
-        material: materials.add(LandMaterial {
-            time: 0.,
+
+    if SPAWN_PLANET {
+        spawn_planet(&mut commands, &mut meshes, &mut standard_materials);
+    }
+    // otherwise the land is grown incrementally by stream_terrain_chunks
+    // as the orbit camera's target moves, rather than spawned here
+}
+
+// chunks within this many grid steps of the camera target stay spawned
+const CHUNK_VIEW_RADIUS: i32 = 4;
+// chunks farther than this drop to the coarse LOD tier
+const CHUNK_LOD_DISTANCE: i32 = 2;
+
+const CHUNK_SIZE: f32 = 100.0;
+// From<Land> for Mesh only spans half of size, so chunks are spaced by that
+const CHUNK_SPAN: f32 = CHUNK_SIZE / 2.0;
+const CHUNK_VERTICES_NEAR: u32 = 100;
+const CHUNK_VERTICES_FAR: u32 = 20;
+
+// a streamed tile of the terrain grid, keyed by its grid coordinate
+#[derive(Component, Debug, Copy, Clone, PartialEq)]
+pub struct TerrainChunk {
+    coord: IVec2,
+    size: f32,
+    num_vertices: u32,
+}
+
+// spawns chunks within CHUNK_VIEW_RADIUS of the camera target, despawns the
+// rest, and drops distant chunks to a coarser vertex count as a simple LOD
+pub fn stream_terrain_chunks(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<LandMaterial>>,
+    camera_query: Query<&LookTransform>,
+    chunk_query: Query<(Entity, &TerrainChunk)>,
+) {
+    // setup_world spawns a Planet instead of the flat land when this is set;
+    // don't also stream an infinite land grid underneath/around it
+    if SPAWN_PLANET {
+        return;
+    }
+
+    let Ok(look_transform) = camera_query.get_single() else {
+        return;
+    };
+    let target = look_transform.target;
+    let center = IVec2::new(
+        (target.x / CHUNK_SPAN).round() as i32,
+        (target.z / CHUNK_SPAN).round() as i32,
+    );
+
+    let mut wanted = HashSet::new();
+    for dx in -CHUNK_VIEW_RADIUS..=CHUNK_VIEW_RADIUS {
+        for dz in -CHUNK_VIEW_RADIUS..=CHUNK_VIEW_RADIUS {
+            wanted.insert(center + IVec2::new(dx, dz));
+        }
+    }
+
+    let lod_vertices = |coord: IVec2| {
+        if (coord - center).abs().max_element() > CHUNK_LOD_DISTANCE {
+            CHUNK_VERTICES_FAR
+        } else {
+            CHUNK_VERTICES_NEAR
+        }
+    };
+
+    // chunks out of view, or whose LOD tier changed, are despawned (and respawned below)
+    let mut present = HashSet::new();
+    for (entity, chunk) in chunk_query.iter() {
+        if wanted.contains(&chunk.coord) && chunk.num_vertices == lod_vertices(chunk.coord) {
+            present.insert(chunk.coord);
+        } else {
+            commands.entity(entity).despawn();
+        }
+    }
+
+    for coord in wanted.iter().filter(|coord| !present.contains(coord)) {
+        spawn_terrain_chunk(
+            &mut commands,
+            &mut meshes,
+            &mut materials,
+            TerrainChunk {
+                coord: *coord,
+                size: CHUNK_SIZE,
+                num_vertices: lod_vertices(*coord),
+            },
+        );
+    }
+}
+
+fn spawn_terrain_chunk(
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<LandMaterial>>,
+    chunk: TerrainChunk,
+) {
+    let land_shape = Land {
+        size: chunk.size,
+        num_vertices: chunk.num_vertices,
+    };
+    let mut mesh = Mesh::from(land_shape);
+    if let Some(VertexAttributeValues::Float32x3(positions)) =
+        mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+    {
+        let colors: Vec<[f32; 4]> = positions
+            .iter()
+            .map(|[r, g, b]| [(1. - *r) / 2., (1. - *g) / 2., (1. - *b) / 2., 1.])
+            .collect();
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+    }
+
+    // offsetting the noise sample domain by the chunk's world position keeps
+    // adjacent chunks' fBm continuous across the seam
+    let world_xz = chunk.coord.as_vec2() * CHUNK_SPAN;
+
+    commands.spawn((
+        MaterialMeshBundle {
+            mesh: meshes.add(mesh),
+            transform: Transform::from_xyz(world_xz.x, 0.5, world_xz.y),
+            material: materials.add(LandMaterial {
+                time: 0.,
+                noise_settings: NoiseSettings {
+                    octaves: 6,
+                    persistence: 0.5,
+                    lacunarity: 2.0,
+                    base_roughness: 1.0,
+                    strength: 1.0,
+                    min_value: 0.0,
+                    offset: world_xz,
+                },
+                epsilon: land_shape.jump(),
+                warp_strength: 0.1,
+                water_level: 0.1,
+            }),
+            ..default()
+        },
+        chunk,
+    ));
+}
+
+fn spawn_planet(
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    standard_materials: &mut ResMut<Assets<StandardMaterial>>,
+) {
+    let planet = Mesh::from(Planet {
+        radius: 20.0,
+        subdivisions: 6,
+    });
+
+    commands.spawn(PbrBundle {
+        mesh: meshes.add(planet),
+        transform: Transform::from_xyz(0.0, 0.0, 0.0),
+        material: standard_materials.add(StandardMaterial {
+            base_color: Color::WHITE,
+            ..default()
         }),
         ..default()
     });
@@ -77,10 +213,35 @@ impl Material for LandMaterial {
 
 // This is the struct that will be passed to your shader
 #[derive(AsBindGroup, TypeUuid, Debug, Clone)]
+#[cfg_attr(feature = "editor", derive(bevy::reflect::Reflect))]
 #[uuid = "f690fdae-d598-45ab-8225-97e2a3f056e0"]
 pub struct LandMaterial {
     #[uniform(0)]
     time: f32,
+    #[uniform(0)]
+    noise_settings: NoiseSettings,
+    // sample offset for the shader's central-difference normal, should match Land::jump
+    #[uniform(0)]
+    epsilon: f32,
+    // strength of the domain-warped ripple layer below water_level
+    #[uniform(0)]
+    warp_strength: f32,
+    // height below which the ripple layer is applied
+    #[uniform(0)]
+    water_level: f32,
+}
+
+// fBm parameters sampled in land_vertex_shader.wgsl
+#[derive(ShaderType, Debug, Clone)]
+#[cfg_attr(feature = "editor", derive(bevy::reflect::Reflect))]
+pub struct NoiseSettings {
+    octaves: u32,
+    persistence: f32,
+    lacunarity: f32,
+    base_roughness: f32,
+    strength: f32,
+    min_value: f32,
+    offset: Vec2,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -89,6 +250,13 @@ struct Land {
     num_vertices: u32,
 }
 
+impl Land {
+    // world-space spacing between neighbouring vertices
+    fn jump(&self) -> f32 {
+        (self.size / 2.0) / self.num_vertices as f32
+    }
+}
+
 impl From<Land> for Mesh {
     fn from(plane: Land) -> Self {
         let extent = plane.size / 2.0;
@@ -104,7 +272,7 @@ impl From<Land> for Mesh {
                         0.0,
                         y as f32 * jump - 0.5 * extent,
                     ],  // increments from -x to +x, e.g -5 to +5
-                    [0.0, 1.0, 0.0], // Normals
+                    [0.0, 1.0, 0.0], // base normal; recomputed per-vertex in land_vertex_shader.wgsl once fBm displaces the height
                     [
                         x as f32
                             / plane.num_vertices as f32,
@@ -174,4 +342,150 @@ impl From<Land> for Mesh {
         mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
         mesh
     }
+}
+
+// a CPU-displaced icosphere, mirroring Land's generator/mesh-conversion shape
+#[derive(Debug, Copy, Clone)]
+struct Planet {
+    radius: f32,
+    subdivisions: u32,
+}
+
+// angular step used to finite-difference the displaced radius into a normal
+const PLANET_NORMAL_EPSILON: f32 = 0.01;
+
+// displaces direction (a unit vector from the planet's center) out to the fBm-mountainous radius
+fn displaced_planet_point(direction: Vec3, radius: f32, settings: &NoiseSettings) -> Vec3 {
+    direction * (radius + fbm_3d(direction, settings))
+}
+
+// an arbitrary pair of unit vectors tangent to the sphere at direction
+fn tangent_basis(direction: Vec3) -> (Vec3, Vec3) {
+    let helper = if direction.x.abs() < 0.99 { Vec3::X } else { Vec3::Y };
+    let t1 = direction.cross(helper).normalize();
+    let t2 = direction.cross(t1);
+    (t1, t2)
+}
+
+impl From<Planet> for Mesh {
+    fn from(planet: Planet) -> Self {
+        let mut mesh = SphereMeshBuilder::new(
+            planet.radius,
+            SphereKind::Ico {
+                subdivisions: planet.subdivisions as usize,
+            },
+        )
+        .build();
+
+        let noise_settings = NoiseSettings {
+            octaves: 6,
+            persistence: 0.5,
+            lacunarity: 2.0,
+            base_roughness: 1.0,
+            strength: planet.radius * 0.05,
+            min_value: 0.0,
+            offset: Vec2::ZERO,
+        };
+
+        if let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        {
+            // recompute the normal from the same fBm, or lighting uses the sphere's flat pre-displacement normals
+            let (displaced, normals): (Vec<[f32; 3]>, Vec<[f32; 3]>) = positions
+                .iter()
+                .map(|p| {
+                    let direction = Vec3::from(*p).normalize();
+                    let (t1, t2) = tangent_basis(direction);
+
+                    let p0 = displaced_planet_point(direction, planet.radius, &noise_settings);
+                    let p1 = displaced_planet_point(
+                        (direction + t1 * PLANET_NORMAL_EPSILON).normalize(),
+                        planet.radius,
+                        &noise_settings,
+                    );
+                    let p2 = displaced_planet_point(
+                        (direction + t2 * PLANET_NORMAL_EPSILON).normalize(),
+                        planet.radius,
+                        &noise_settings,
+                    );
+
+                    let mut normal = (p1 - p0).cross(p2 - p0).normalize();
+                    if normal.dot(direction) < 0.0 {
+                        normal = -normal;
+                    }
+
+                    (p0.into(), normal.into())
+                })
+                .unzip();
+
+            mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, displaced);
+            mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        }
+
+        if let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        {
+            let colors: Vec<[f32; 4]> = positions
+                .iter()
+                .map(|p| {
+                    let radius = Vec3::from(*p).length();
+                    // fbm_3d is clamped at zero, so ocean vertices sit at the base radius (up to rounding)
+                    if radius <= planet.radius + 1e-3 {
+                        [0.1, 0.3, 0.6, 1.0] // ocean
+                    } else {
+                        [0.3, 0.5, 0.2, 1.0] // land
+                    }
+                })
+                .collect();
+            mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+        }
+
+        // degenerate UV triangles can make this fail; skip tangents rather than panic
+        if let Err(error) = mesh.generate_tangents() {
+            bevy::log::warn!("failed to generate planet mesh tangents: {error}");
+        }
+        mesh
+    }
+}
+
+// CPU-side counterpart to fbm_height in land_vertex_shader.wgsl, for Planet
+fn fbm_3d(point: Vec3, settings: &NoiseSettings) -> f32 {
+    let noise = Simplex::new(0);
+
+    let mut frequency = settings.base_roughness;
+    let mut amplitude = 1.0;
+    let mut value = 0.0;
+
+    for _ in 0..settings.octaves {
+        let sample = point * frequency;
+        let v = noise.get([sample.x as f64, sample.y as f64, sample.z as f64]) as f32;
+        value += v * amplitude;
+        frequency *= settings.lacunarity;
+        amplitude *= settings.persistence;
+    }
+
+    (value - settings.min_value).max(0.0) * settings.strength
+}
+
+// advances every LandMaterial's time uniform from the app's Time
+pub fn animate_terrain_time(time: Res<Time>, mut materials: ResMut<Assets<LandMaterial>>) {
+    let elapsed = time.elapsed_seconds();
+    for (_, material) in materials.iter_mut() {
+        material.time = elapsed;
+    }
+}
+
+// live terrain tuning panel, enabled with --features editor
+#[cfg(feature = "editor")]
+pub struct TerrainEditorPlugin;
+
+#[cfg(feature = "editor")]
+impl bevy::prelude::Plugin for TerrainEditorPlugin {
+    fn build(&self, app: &mut bevy::prelude::App) {
+        use bevy_inspector_egui::quick::AssetInspectorPlugin;
+
+        app.register_type::<LandMaterial>()
+            .register_type::<NoiseSettings>()
+            .add_plugins(AssetInspectorPlugin::<LandMaterial>::default());
+    }
 }
\ No newline at end of file